@@ -0,0 +1,189 @@
+//! On-disk incremental compilation cache.
+//!
+//! Warm rebuilds spend almost all of their time re-scanning, re-parsing and
+//! re-compiling files that did not change since the last run. This module keeps
+//! a small manifest next to the output (a `.clue-cache/` directory) that maps
+//! each file's `realname` to a digest of its inputs and the Lua it last
+//! produced. When the digest still matches, `compile_folder` can push the
+//! cached Lua straight into the output and skip the whole pipeline.
+
+use ahash::AHashMap;
+use clue_core::env::Options;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Name of the cache directory created next to the compiled output.
+const CACHE_DIR: &str = ".clue-cache";
+
+/// Fixed seeds so digests stay stable across process runs (the default
+/// `RandomState` seeds are randomised and would bust the cache every time).
+const SEEDS: (u64, u64, u64, u64) = (
+	0x51_7c_c1_b7_27_22_0a_95,
+	0x24_07_e7_2b_d5_4c_89_3d,
+	0x2d_98_d3_3b_89_9e_8a_11,
+	0x9e_37_79_b9_7f_4a_7c_15,
+);
+
+fn hasher() -> ahash::AHasher {
+	use std::hash::BuildHasher;
+	ahash::RandomState::with_seeds(SEEDS.0, SEEDS.1, SEEDS.2, SEEDS.3).build_hasher()
+}
+
+/// Digest of the effective options that influence codegen. Print-only flags
+/// (`env_tokens`, `env_struct`, `env_output`) are intentionally left out: they
+/// don't change the Lua that ends up on disk.
+pub fn options_digest(options: &Options) -> u64 {
+	let mut hasher = hasher();
+	options.env_jitbit.hash(&mut hasher);
+	format!("{:?}", options.env_continue).hash(&mut hasher);
+	options.env_rawsetglobals.hash(&mut hasher);
+	options.env_debug.hash(&mut hasher);
+	hasher.finish()
+}
+
+/// Digest of a file's inputs: its `realname`, its raw source, the options
+/// digest and the contents of any files pulled in through `@include`
+/// directives, so a changed dependency busts the cache even when the file
+/// itself is untouched. The `realname` is folded in so two files with identical
+/// source in the same directory get distinct digests and never share a blob.
+///
+/// `import "…"` module dependencies are deliberately *not* folded in: import
+/// rewriting never inlines the imported module's source, it only emits
+/// `__modules["x"]()` lookups, so a changed dependency produces its own new
+/// cached chunk and cannot stale this one. If imports ever start inlining
+/// source, they must be hashed here too or warm rebuilds would serve stale Lua.
+pub fn input_digest(realname: &str, source: &str, options_digest: u64, base: &Path) -> u64 {
+	let mut hasher = hasher();
+	realname.hash(&mut hasher);
+	source.hash(&mut hasher);
+	options_digest.hash(&mut hasher);
+	for included in included_files(source, base) {
+		if let Ok(contents) = fs::read(&included) {
+			included.to_string_lossy().hash(&mut hasher);
+			contents.hash(&mut hasher);
+		}
+	}
+	hasher.finish()
+}
+
+/// Collect the paths referenced by `@include "..."` preprocessor directives,
+/// resolved relative to the including file's directory.
+fn included_files(source: &str, base: &Path) -> Vec<PathBuf> {
+	let mut files = Vec::new();
+	for line in source.lines() {
+		let line = line.trim_start();
+		let Some(rest) = line.strip_prefix("@include") else {
+			continue;
+		};
+		if let Some(start) = rest.find('"') {
+			if let Some(len) = rest[start + 1..].find('"') {
+				let name = &rest[start + 1..start + 1 + len];
+				files.push(base.join(name));
+			}
+		}
+	}
+	files
+}
+
+/// A loaded cache manifest. The compiled Lua for each entry lives in its own
+/// `<digest>.lua` file inside the cache directory so the manifest itself stays
+/// a compact list of `digest<TAB>realname` lines.
+pub struct Cache {
+	dir: PathBuf,
+	options_digest: u64,
+	entries: AHashMap<String, u64>,
+	dirty: bool,
+}
+
+impl Cache {
+	/// Load the cache sitting next to `output_dir`. A change in the options
+	/// digest invalidates the whole manifest, matching the guarantee that
+	/// recompilation happens whenever the effective options change.
+	pub fn load(output_dir: &Path, options_digest: u64) -> Self {
+		let dir = output_dir.join(CACHE_DIR);
+		let mut entries = AHashMap::new();
+		let mut valid = false;
+		if let Ok(manifest) = fs::read_to_string(dir.join("manifest")) {
+			let mut lines = manifest.lines();
+			if lines.next().and_then(|l| l.parse::<u64>().ok()) == Some(options_digest) {
+				valid = true;
+				for line in lines {
+					if let Some((digest, realname)) = line.split_once('\t') {
+						if let Ok(digest) = digest.parse() {
+							entries.insert(realname.to_owned(), digest);
+						}
+					}
+				}
+			}
+		}
+		Self {
+			dir,
+			options_digest,
+			entries: if valid { entries } else { AHashMap::new() },
+			dirty: false,
+		}
+	}
+
+	/// Return the cached Lua for `realname` if its stored digest matches
+	/// `digest`, otherwise `None`.
+	pub fn get(&self, realname: &str, digest: u64) -> Option<String> {
+		if self.entries.get(realname) != Some(&digest) {
+			return None;
+		}
+		fs::read_to_string(self.dir.join(format!("{digest}.lua"))).ok()
+	}
+
+	/// Record freshly compiled Lua for `realname` under `digest`.
+	pub fn insert(&mut self, realname: String, digest: u64, lua: &str) {
+		if fs::create_dir_all(&self.dir).is_err() {
+			return;
+		}
+		if fs::write(self.dir.join(format!("{digest}.lua")), lua).is_ok() {
+			self.entries.insert(realname, digest);
+			self.dirty = true;
+		}
+	}
+
+	/// Write the manifest back atomically (to a temp file, then rename) so an
+	/// interrupted run can never leave a half-written manifest behind.
+	pub fn save(&self) -> io::Result<()> {
+		if !self.dirty {
+			return Ok(());
+		}
+		fs::create_dir_all(&self.dir)?;
+		let mut manifest = format!("{}\n", self.options_digest);
+		for (realname, digest) in &self.entries {
+			manifest += &format!("{digest}\t{realname}\n");
+		}
+		let tmp = self.dir.join("manifest.tmp");
+		fs::write(&tmp, manifest)?;
+		fs::rename(tmp, self.dir.join("manifest"))?;
+		self.prune_blobs();
+		Ok(())
+	}
+
+	/// Delete any `<digest>.lua` blob that the final manifest no longer
+	/// references, so a long-lived `.clue-cache/` doesn't accumulate one orphan
+	/// per edit. Failures here are non-fatal: a stale blob wastes disk but never
+	/// breaks a build.
+	fn prune_blobs(&self) {
+		let referenced: std::collections::HashSet<String> = self
+			.entries
+			.values()
+			.map(|digest| format!("{digest}.lua"))
+			.collect();
+		let Ok(dir) = fs::read_dir(&self.dir) else {
+			return;
+		};
+		for entry in dir.flatten() {
+			let name = entry.file_name();
+			let Some(name) = name.to_str() else {
+				continue;
+			};
+			if name.ends_with(".lua") && !referenced.contains(name) {
+				let _ = fs::remove_file(entry.path());
+			}
+		}
+	}
+}
@@ -0,0 +1,88 @@
+//! Project configuration file support.
+//!
+//! Every build option used to live only on the command line. This module looks
+//! for a `clue.toml` (or, when the `mlua` feature is on, a `clue.lua` evaluated
+//! in a sandboxed interpreter) by walking from the target path upward, so a
+//! team can commit one configuration instead of memorising long invocations.
+//! The resolved values sit *below* the command line: CLI flags override file
+//! values, which in turn override the built-in defaults.
+
+use serde::Deserialize;
+use std::path::Path;
+
+/// The options a `clue.toml`/`clue.lua` may set. Every field is optional so a
+/// config can override just the settings it cares about and leave the rest to
+/// the CLI defaults.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+	pub outputname: Option<String>,
+	pub base: Option<String>,
+	pub r#continue: Option<String>,
+	pub jitbit: Option<String>,
+	pub rawsetglobals: Option<bool>,
+	pub debug: Option<bool>,
+}
+
+impl Config {
+	/// Search from `start` (a file or directory) upward through its ancestors
+	/// for a `clue.toml`/`clue.lua`, returning the first one found, or an empty
+	/// config when none exists. An unreadable or malformed config is reported as
+	/// an error so typos don't silently fall back to the defaults.
+	pub fn find(start: &Path) -> Result<Self, String> {
+		let mut dir: &Path = if start.is_dir() {
+			start
+		} else {
+			start.parent().unwrap_or_else(|| Path::new("."))
+		};
+		loop {
+			let toml = dir.join("clue.toml");
+			if toml.is_file() {
+				return Self::from_toml(&toml);
+			}
+			#[cfg(feature = "mlua")]
+			{
+				let lua = dir.join("clue.lua");
+				if lua.is_file() {
+					return Self::from_lua(&lua);
+				}
+			}
+			match dir.parent() {
+				Some(parent) => dir = parent,
+				None => return Ok(Self::default()),
+			}
+		}
+	}
+
+	fn from_toml(path: &Path) -> Result<Self, String> {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|e| format!("Failed to read \"{}\": {e}", path.display()))?;
+		toml::from_str(&contents)
+			.map_err(|e| format!("Failed to parse \"{}\": {e}", path.display()))
+	}
+
+	/// Evaluate a `clue.lua` in a fresh interpreter and read the build options
+	/// back out of its globals. Only Lua's base library is opened (no `io`, `os`
+	/// or `package`) so a config file can't run arbitrary I/O.
+	#[cfg(feature = "mlua")]
+	fn from_lua(path: &Path) -> Result<Self, String> {
+		let contents = std::fs::read_to_string(path)
+			.map_err(|e| format!("Failed to read \"{}\": {e}", path.display()))?;
+		let lua = mlua::Lua::new_with(mlua::StdLib::NONE, mlua::LuaOptions::default())
+			.map_err(|e| format!("Failed to create Lua interpreter: {e}"))?;
+		lua.load(&contents)
+			.exec()
+			.map_err(|e| format!("Failed to evaluate \"{}\": {e}", path.display()))?;
+		let globals = lua.globals();
+		let get_string = |key: &str| globals.get::<_, Option<String>>(key).ok().flatten();
+		let get_bool = |key: &str| globals.get::<_, Option<bool>>(key).ok().flatten();
+		Ok(Self {
+			outputname: get_string("outputname"),
+			base: get_string("base"),
+			r#continue: get_string("continue"),
+			jitbit: get_string("jitbit"),
+			rawsetglobals: get_bool("rawsetglobals"),
+			debug: get_bool("debug"),
+		})
+	}
+}
@@ -0,0 +1,96 @@
+//! Emitting intermediate representations to files.
+//!
+//! `--tokens`, `--struct` and `--output` only pretty-print to the console,
+//! which is fine for debugging but useless to other tooling. `--emit
+//! <STAGE>=<PATH>` instead serialises a pipeline stage to a file — the scanner
+//! token stream and the parsed structure as JSON (so editors, linters and
+//! formatters can consume them), the compiled Lua as plain text. Paired with
+//! `--stop-after`, the pipeline can halt right after preprocessing, scanning or
+//! parsing, turning Clue's internals into a reusable interface.
+
+use clap::ValueEnum;
+#[cfg(feature = "emit-json")]
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// A pipeline stage the compiler can stop after, skipping everything that
+/// follows it (including the compiler itself).
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Stage {
+	/// Stop right after running the preprocessor.
+	Preprocess,
+	/// Stop after scanning, once the token stream exists.
+	Tokens,
+	/// Stop after parsing, once the structure exists.
+	Ast,
+}
+
+/// Where each representation should be written, plus an optional early stop.
+#[derive(Default)]
+pub struct Emit {
+	pub tokens: Option<PathBuf>,
+	pub ast: Option<PathBuf>,
+	pub lua: Option<PathBuf>,
+	pub stop_after: Option<Stage>,
+}
+
+impl Emit {
+	/// Build the emit configuration from the raw `--emit STAGE=PATH` arguments
+	/// and an optional `--stop-after` stage.
+	pub fn new(args: &[String], stop_after: Option<Stage>) -> Result<Self, String> {
+		let mut emit = Emit {
+			stop_after,
+			..Default::default()
+		};
+		for arg in args {
+			let (stage, path) = arg
+				.split_once('=')
+				.ok_or_else(|| format!("Invalid --emit value \"{arg}\", expected STAGE=PATH"))?;
+			let slot = match stage {
+				"tokens" => &mut emit.tokens,
+				"ast" => &mut emit.ast,
+				"lua" => &mut emit.lua,
+				other => return Err(format!("Unknown emit stage \"{other}\"")),
+			};
+			*slot = Some(PathBuf::from(path));
+		}
+		Ok(emit)
+	}
+
+	/// Whether any emit target or early stop was requested. Used to reject
+	/// `--emit`/`--stop-after` against a directory target, where per-file
+	/// semantics would be ambiguous, rather than silently ignoring them.
+	pub fn is_requested(&self) -> bool {
+		self.tokens.is_some()
+			|| self.ast.is_some()
+			|| self.lua.is_some()
+			|| self.stop_after.is_some()
+	}
+}
+
+/// Serialise a scanner/parser stage as pretty JSON to `path`.
+///
+/// The scanner `Token` stream and the parsed structure live in the external
+/// `clue_core` crate, which only derives `serde::Serialize` under its own serde
+/// support. JSON emission is therefore gated behind the `emit-json` feature
+/// (which pulls that support in); without it `--emit tokens=`/`ast=` reports a
+/// clear error rather than making the whole crate fail to build.
+#[cfg(feature = "emit-json")]
+pub fn write_json<T: Serialize>(path: &PathBuf, value: &T) -> Result<(), String> {
+	let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+	std::fs::write(path, json).map_err(|e| format!("Failed to write \"{}\": {e}", path.display()))
+}
+
+/// Fallback used when the crate is built without the `emit-json` feature, so
+/// requesting a JSON stage fails loudly instead of silently doing nothing.
+#[cfg(not(feature = "emit-json"))]
+pub fn write_json<T>(_path: &PathBuf, _value: &T) -> Result<(), String> {
+	Err(String::from(
+		"--emit tokens/ast requires building Clue with the \"emit-json\" feature",
+	))
+}
+
+/// Write `text` verbatim to `path`.
+pub fn write_text(path: &PathBuf, text: &str) -> Result<(), String> {
+	std::fs::write(path, text).map_err(|e| format!("Failed to write \"{}\": {e}", path.display()))
+}
@@ -1,13 +1,21 @@
 use ahash::AHashMap;
-use clap::{crate_version, Parser};
+use clap::parser::ValueSource;
+use clap::{crate_version, CommandFactory, FromArgMatches, Parser, ValueEnum};
 use clue::env::{ContinueMode, Options};
 use clue::{check, compiler::*, format_clue, parser::*, preprocessor::*, scanner::*, /*, LUA_G*/};
 use clue_core as clue;
 use std::cmp::min;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::thread::spawn;
 use std::{ffi::OsStr, fmt::Display, fs, fs::File, io::prelude::*, path::Path, time::Instant};
 
+mod cache;
+mod config;
+mod emit;
+mod imports;
+mod tester;
+
 macro_rules! println {
     ($($rest:tt)*) => {
         std::println!($($rest)*)
@@ -18,9 +26,14 @@ macro_rules! println {
 #[clap(
 	version,
 	about = "C/Rust like programming language that compiles into Lua code\nMade by Maiori\nhttps://github.com/ClueLang/Clue",
-	long_about = None
+	long_about = None,
+	subcommand_negates_reqs = true,
 )]
 struct Cli {
+	/// Subcommands. When absent, Clue compiles the given path as usual.
+	#[clap(subcommand)]
+	command: Option<Command>,
+
 	/// The path to the directory where the *.clue files are located.
 	/// Every directory inside the given directory will be checked too.
 	/// If the path points to a single *.clue file, only that file will be compiled.
@@ -99,17 +112,41 @@ struct Cli {
 		)]
 		std: LuaSTD,
 	*/
+	/// Keep running after the first compile and recompile when files change
+	#[clap(short, long)]
+	watch: bool,
+
+	/// Serialize a pipeline stage to a file, e.g. --emit tokens=toks.json
+	/// (stages: tokens, ast, lua; tokens/ast are written as JSON)
+	#[clap(long, value_name = "STAGE=PATH")]
+	emit: Vec<String>,
+
+	/// Stop the pipeline after the given stage without running the compiler
+	#[clap(long, value_enum, value_name = "STAGE")]
+	stop_after: Option<emit::Stage>,
+
 	#[cfg(feature = "mlua")]
 	/// Execute the output Lua code once it's compiled
 	#[clap(short, long)]
 	execute: bool,
 }
 
+#[derive(clap::Subcommand)]
+enum Command {
+	/// Run the `.clue` snapshot/expectation tests under a directory
+	Test {
+		/// The directory to search for `.clue` test files.
+		#[clap(default_value = ".")]
+		path: String,
+	},
+}
+
 fn compile_code(
 	mut code: String,
 	name: String,
 	scope: usize,
 	options: &Options,
+	emit: &emit::Emit,
 ) -> Result<String, String> {
 	let time = Instant::now();
 	if to_preprocess(&code) {
@@ -118,10 +155,19 @@ fn compile_code(
 			.iter()
 			.collect();
 	}
+	if emit.stop_after == Some(emit::Stage::Preprocess) {
+		return Ok(String::new());
+	}
 	let tokens: Vec<Token> = scan_code(code, name.clone())?;
+	if let Some(path) = &emit.tokens {
+		emit::write_json(path, &tokens)?;
+	}
 	if options.env_tokens {
 		println!("Scanned tokens of file \"{}\":\n{:#?}", name, tokens);
 	}
+	if emit.stop_after == Some(emit::Stage::Tokens) {
+		return Ok(String::new());
+	}
 	let (ctokens, statics) = parse_tokens(
 		tokens,
 		/*if flag!(env_types) != TypesMode::NONE {
@@ -133,9 +179,15 @@ fn compile_code(
 		options,
 	)?;
 
+	if let Some(path) = &emit.ast {
+		emit::write_json(path, &ctokens)?;
+	}
 	if options.env_struct {
 		println!("Parsed structure of file \"{}\":\n{:#?}", name, ctokens);
 	}
+	if emit.stop_after == Some(emit::Stage::Ast) {
+		return Ok(String::new());
+	}
 
 	let compiler = Compiler::new(options);
 	let code = compiler.compile_tokens(scope, ctokens);
@@ -148,7 +200,11 @@ fn compile_code(
 		name,
 		time.elapsed().as_secs_f32()
 	);
-	Ok(statics + &code)
+	let output = statics + &code;
+	if let Some(path) = &emit.lua {
+		emit::write_text(path, &output)?;
+	}
+	Ok(output)
 }
 
 fn compile_file<P: AsRef<Path>>(
@@ -156,13 +212,14 @@ fn compile_file<P: AsRef<Path>>(
 	name: String,
 	scope: usize,
 	options: &Options,
+	emit: &emit::Emit,
 ) -> Result<String, String>
 where
 	P: AsRef<OsStr> + Display,
 {
 	let mut code: String = String::with_capacity(512);
 	check!(check!(File::open(path)).read_to_string(&mut code));
-	compile_code(code, name, scope, options)
+	compile_code(code, name, scope, options, emit)
 }
 
 fn check_for_files<P: AsRef<Path>>(
@@ -202,11 +259,15 @@ fn compile_folder<P: AsRef<Path>>(
 where
 	P: AsRef<OsStr> + Display,
 {
+	let output_dir = PathBuf::from(path.as_ref());
 	let files = Arc::new(Mutex::new(check!(check_for_files(path, rpath))));
 	let threads_count = min(files.lock().unwrap().len(), num_cpus::get() * 2);
 	let errored = Arc::new(Mutex::new(0u8));
 	let output = Arc::new(Mutex::new(Vec::with_capacity(files.lock().unwrap().len())));
 
+	let options_digest = cache::options_digest(options);
+	let cache = Arc::new(Mutex::new(cache::Cache::load(&output_dir, options_digest)));
+
 	let mut threads = Vec::with_capacity(threads_count);
 	for _ in 0..threads_count {
 		// this `.clone()` is used to create a new pointer to the outside `files`
@@ -215,6 +276,7 @@ where
 		let files = files.clone();
 		let errored = errored.clone();
 		let output = output.clone();
+		let cache = cache.clone();
 
 		let thread = spawn(move || loop {
 			// Acquire the lock, check the files to compile, get the file to compile and then drop the lock
@@ -225,7 +287,36 @@ where
 				}
 				files.pop().unwrap()
 			};
-			let code = match compile_file(&filename, filename.clone(), 2, &options) {
+
+			// Read the source up front so its digest can be checked against the
+			// cache before spending anything on the scan/parse/compile pipeline.
+			let mut source = String::with_capacity(512);
+			if let Err(e) = File::open(&filename).and_then(|mut f| f.read_to_string(&mut source)) {
+				*errored.lock().unwrap() += 1;
+				println!("Error: {}", e);
+				continue;
+			}
+			let base = Path::new(&filename)
+				.parent()
+				.unwrap_or_else(|| Path::new("."));
+			let digest = cache::input_digest(&realname, &source, options_digest, base);
+
+			// Rewrite `import` directives into module-table lookups
+			// and record the dependency edges so the entries can be ordered.
+			let (rewritten, deps) = imports::rewrite(&source);
+			let module = realname.strip_suffix(".clue").unwrap().to_owned();
+
+			if let Some(cached) = cache.lock().unwrap().get(&realname, digest) {
+				println!("Reused cached file \"{}\"", filename);
+				output.lock().unwrap().push(imports::Entry {
+					module,
+					deps,
+					chunk: cached,
+				});
+				continue;
+			}
+
+			let code = match compile_code(rewritten, filename.clone(), 2, &options, &emit::Emit::default()) {
 				Ok(t) => t,
 				Err(e) => {
 					*errored.lock().unwrap() += 1;
@@ -234,14 +325,13 @@ where
 				}
 			};
 
-			let string = format_clue!(
-				"\t[\"",
-				realname.strip_suffix(".clue").unwrap(),
-				"\"] = function()\n",
-				code,
-				"\n\tend,\n"
-			);
-			output.lock().unwrap().push(string);
+			let string = format_clue!("\t[\"", &module, "\"] = function()\n", code, "\n\tend,\n");
+			cache.lock().unwrap().insert(realname, digest, &string);
+			output.lock().unwrap().push(imports::Entry {
+				module,
+				deps,
+				chunk: string,
+			});
 		});
 		threads.push(thread);
 	}
@@ -250,9 +340,18 @@ where
 		thread.join().unwrap();
 	}
 
+	if let Err(e) = cache.lock().unwrap().save() {
+		println!("Warning: failed to write compilation cache: {}", e);
+	}
+
 	let errored = *errored.lock().unwrap();
 	match errored {
-		0 => Ok(output.lock().unwrap().drain(..).collect()),
+		0 => {
+			// Order the entries so imported modules are emitted before the
+			// files that depend on them, dropping anything no entry point reaches.
+			let entries = output.lock().unwrap().drain(..).collect();
+			imports::resolve(entries)
+		}
 		1 => Err(String::from("1 file failed to compile!")),
 		n => Err(format!("{n} files failed to compile!")),
 	}
@@ -271,7 +370,8 @@ fn execute_lua_code(code: &str) {
 
 fn main() -> Result<(), String> {
 	std::env::set_var("CLUE_VERSION", crate_version!());
-	let cli = Cli::parse();
+	let matches = Cli::command().get_matches();
+	let mut cli = Cli::from_arg_matches(&matches).map_err(|e| e.to_string())?;
 	if cli.license {
 		println!(include_str!("../../LICENSE"));
 		return Ok(());
@@ -280,6 +380,48 @@ fn main() -> Result<(), String> {
 		return Err(String::from("Type checking is not supported yet!"));
 	}
 
+	if let Some(Command::Test { path }) = &cli.command {
+		let options = Options {
+			env_jitbit: cli.jitbit.clone(),
+			env_continue: cli.r#continue,
+			env_rawsetglobals: cli.rawsetglobals,
+			env_debug: cli.debug,
+			..Default::default()
+		};
+		return tester::run(path, &options);
+	}
+
+	// Layer a discovered `clue.toml`/`clue.lua` in between the built-in
+	// defaults and the command line: any flag the user actually passed wins,
+	// otherwise the config file's value is used.
+	if let Some(path) = &cli.path {
+		let config = config::Config::find(Path::new(path))?;
+		let given = |name: &str| matches.value_source(name) == Some(ValueSource::CommandLine);
+		if !given("outputname") {
+			if let Some(outputname) = config.outputname {
+				cli.outputname = outputname;
+			}
+		}
+		if !given("base") {
+			cli.base = config.base.or(cli.base);
+		}
+		if !given("continue") {
+			if let Some(mode) = config.r#continue {
+				cli.r#continue = ContinueMode::from_str(&mode, true)
+					.map_err(|_| format!("Invalid continue mode in config: \"{mode}\""))?;
+			}
+		}
+		if !given("jitbit") {
+			cli.jitbit = config.jitbit.or(cli.jitbit);
+		}
+		if !given("rawsetglobals") {
+			cli.rawsetglobals = config.rawsetglobals.unwrap_or(cli.rawsetglobals);
+		}
+		if !given("debug") {
+			cli.debug = config.debug.unwrap_or(cli.debug);
+		}
+	}
+
 	let options = Options {
 		env_tokens: cli.tokens,
 		env_struct: cli.r#struct,
@@ -290,20 +432,16 @@ fn main() -> Result<(), String> {
 		env_output: cli.output,
 	};
 
-	let mut code = String::with_capacity(512);
-
-	if let Some(bit) = &options.env_jitbit {
-		code += &format!("local {bit} = require(\"bit\");\n");
-	}
 	/*if flag!(env_types) != TypesMode::NONE {
 		*check!(LUA_G.write()) = match flag!(env_std) {
 			LuaSTD::LUA54 => Some(AHashMap::from_iter([(String::from("print"), LuaType::NIL)])), //PLACEHOLDER
 			_ => Some(AHashMap::default()),
 		};
 	}*/
-	let codepath = cli.path.unwrap();
+	let emit = emit::Emit::new(&cli.emit, cli.stop_after)?;
+	let codepath = cli.path.clone().unwrap();
 	if cli.pathiscode {
-		let code = compile_code(codepath, String::from("(command line)"), 0, &options)?;
+		let code = compile_code(codepath, String::from("(command line)"), 0, &options, &emit)?;
 		println!("{}", code);
 		#[cfg(feature = "mlua")]
 		if cli.execute {
@@ -311,17 +449,45 @@ fn main() -> Result<(), String> {
 		}
 		return Ok(());
 	}
-	let path: &Path = Path::new(&codepath);
+
+	build(&codepath, &cli, &options, &emit)?;
+	if cli.watch {
+		watch(&codepath, &cli, &options, &emit)?;
+	}
+	Ok(())
+}
+
+/// Run the directory/file compile pipeline once, writing (and optionally
+/// executing) the output according to `cli`. Split out from `main` so
+/// `--watch` can call it again on every filesystem change.
+fn build(codepath: &str, cli: &Cli, options: &Options, emit: &emit::Emit) -> Result<(), String> {
+	let mut code = String::with_capacity(512);
+	if let Some(bit) = &options.env_jitbit {
+		code += &format!("local {bit} = require(\"bit\");\n");
+	}
+	let path: &Path = Path::new(codepath);
 	let mut compiledname = String::new();
 
 	if path.is_dir() {
+		if emit.is_requested() {
+			return Err(String::from(
+				"--emit and --stop-after only work on a single file, not a directory",
+			));
+		}
 		code += "--STATICS\n";
-		for file in compile_folder(&codepath, String::new(), &options)? {
+		for file in compile_folder(codepath, String::new(), options)? {
 			code += &file;
 		}
 		let (statics, output) = code.rsplit_once("--STATICS").unwrap();
 
-		code = match cli.base {
+		// The compiled entries live in a single place: the module table the
+		// rewritten `import` directives look up. Build it before the base
+		// substitution so `output` is no longer borrowed from `code`, and leave
+		// the base template's own statics slot empty rather than emitting every
+		// `function()` body a second time inside `base.lua`'s table.
+		let modules = format!("local {} = {{\n{output}}}\n", imports::MODULE_TABLE);
+
+		code = match &cli.base {
 			Some(filename) => {
 				let base = match fs::read(filename) {
 					Ok(base) => base,
@@ -330,12 +496,13 @@ fn main() -> Result<(), String> {
 				check!(std::str::from_utf8(&base))
 					.to_string()
 					.replace("--STATICS\n", statics)
-					.replace('§', output)
+					.replace('§', "")
 			}
 			None => include_str!("base.lua")
 				.replace("--STATICS\n", statics)
-				.replace('§', output),
+				.replace('§', ""),
 		};
+		code = modules + &code;
 		if !cli.dontsave {
 			let output_name = &format!(
 				"{}.lua",
@@ -354,10 +521,11 @@ fn main() -> Result<(), String> {
 		}
 	} else if path.is_file() {
 		code = compile_file(
-			&codepath,
+			codepath,
 			path.file_name().unwrap().to_string_lossy().into_owned(),
 			0,
-			&options,
+			options,
+			emit,
 		)?;
 
 		if !cli.dontsave {
@@ -385,6 +553,66 @@ fn main() -> Result<(), String> {
 	Ok(())
 }
 
+/// Watch `codepath` (a directory or single file) and rebuild whenever a
+/// `.clue` file is created, modified or deleted. Bursts of editor saves are
+/// debounced so a single rebuild runs per quiet period, and each cycle is
+/// timed like the rest of the compiler's output. Combined with the on-disk
+/// cache, only changed files are actually recompiled.
+fn watch(codepath: &str, cli: &Cli, options: &Options, emit: &emit::Emit) -> Result<(), String> {
+	use notify::{RecursiveMode, Watcher};
+	use std::sync::mpsc::{channel, RecvTimeoutError};
+	use std::time::Duration;
+
+	/// How long to wait for the filesystem to go quiet before rebuilding.
+	const DEBOUNCE: Duration = Duration::from_millis(200);
+
+	let (tx, rx) = channel();
+	let mut watcher =
+		notify::recommended_watcher(tx).map_err(|e| format!("Failed to start watcher: {e}"))?;
+	let path = Path::new(codepath);
+	let mode = if path.is_dir() {
+		RecursiveMode::Recursive
+	} else {
+		RecursiveMode::NonRecursive
+	};
+	watcher
+		.watch(path, mode)
+		.map_err(|e| format!("Failed to watch \"{codepath}\": {e}"))?;
+
+	// Only events touching a `.clue` file are worth a rebuild.
+	let touches_clue = |event: &notify::Event| {
+		event
+			.paths
+			.iter()
+			.any(|p| p.extension() == Some(OsStr::new("clue")))
+	};
+
+	println!("Watching \"{codepath}\" for changes...");
+	loop {
+		// Block until a relevant change happens, then drain the burst until the
+		// filesystem has been quiet for `DEBOUNCE`.
+		loop {
+			match rx.recv() {
+				Ok(Ok(event)) if touches_clue(&event) => break,
+				Ok(_) => continue,
+				Err(_) => return Ok(()),
+			}
+		}
+		loop {
+			match rx.recv_timeout(DEBOUNCE) {
+				Ok(_) => continue,
+				Err(RecvTimeoutError::Timeout) => break,
+				Err(RecvTimeoutError::Disconnected) => return Ok(()),
+			}
+		}
+		let time = Instant::now();
+		if let Err(e) = build(codepath, cli, options, emit) {
+			println!("Error: {e}");
+		}
+		println!("Rebuilt in {} seconds!", time.elapsed().as_secs_f32());
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use clue_core::env::Options;
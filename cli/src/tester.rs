@@ -0,0 +1,195 @@
+//! Snapshot/expectation test runner for `.clue` files.
+//!
+//! `clue test <dir>` discovers every `.clue` file under a directory and checks
+//! it against inline expectations embedded as directive comments, reusing the
+//! same `compile_code` pipeline the compiler uses. It is deliberately close to
+//! a compiletest harness: each file declares what it expects, the runner
+//! reports the pass/fail tally with diffs and exits non-zero on any failure so
+//! it can gate CI.
+//!
+//! Supported directives (written as comments, each on its own line, so they
+//! don't affect compilation):
+//!
+//! * `--@expect-error: <substring>` — `compile_code` must return an `Err`
+//!   whose message contains `<substring>`.
+//! * `--@expect-output:` followed by `--@ <line>` comment lines — the compiled
+//!   Lua must match those lines. A sibling `<file>.lua.expected` works too.
+//! * `--@expect-run:` followed by `--@ <line>` comment lines — with the `mlua`
+//!   feature, the compiled Lua is executed and its stdout compared (ignored
+//!   without the feature).
+
+use crate::{check_for_files, compile_code};
+use clue_core::env::Options;
+use std::path::Path;
+
+/// What a single test file asserts about its compilation.
+#[derive(Default)]
+struct Expectations {
+	error: Option<String>,
+	output: Option<String>,
+	run: Option<String>,
+}
+
+/// Tracks which `--@ ` continuation block following lines belong to.
+enum Block {
+	None,
+	Output,
+	Run,
+}
+
+fn parse_directives(source: &str) -> Expectations {
+	let mut expectations = Expectations::default();
+	let mut block = Block::None;
+	for line in source.lines() {
+		let line = line.trim_start();
+		if let Some(rest) = line.strip_prefix("--@expect-error:") {
+			expectations.error = Some(rest.trim().to_owned());
+			block = Block::None;
+		} else if line.starts_with("--@expect-output:") {
+			expectations.output.get_or_insert_with(String::new);
+			block = Block::Output;
+		} else if line.starts_with("--@expect-run:") {
+			expectations.run.get_or_insert_with(String::new);
+			block = Block::Run;
+		} else if let Some(content) = line.strip_prefix("--@ ").or_else(|| {
+			// A bare `--@` is an intentional blank line inside a block.
+			(line == "--@").then_some("")
+		}) {
+			let target = match block {
+				Block::Output => expectations.output.as_mut(),
+				Block::Run => expectations.run.as_mut(),
+				Block::None => None,
+			};
+			if let Some(target) = target {
+				target.push_str(content);
+				target.push('\n');
+			}
+		} else {
+			block = Block::None;
+		}
+	}
+	expectations
+}
+
+/// Compare two chunks of text line by line, ignoring trailing whitespace and
+/// surrounding blank lines, returning a short diff when they differ.
+fn diff(expected: &str, got: &str) -> Option<String> {
+	let normalize = |s: &str| {
+		s.lines()
+			.map(|l| l.trim_end().to_owned())
+			.collect::<Vec<_>>()
+			.join("\n")
+			.trim()
+			.to_owned()
+	};
+	let expected = normalize(expected);
+	let got = normalize(got);
+	if expected == got {
+		return None;
+	}
+	let mut report = String::from("    --- expected ---\n");
+	for line in expected.lines() {
+		report += &format!("    - {line}\n");
+	}
+	report += "    --- got ---\n";
+	for line in got.lines() {
+		report += &format!("    + {line}\n");
+	}
+	Some(report)
+}
+
+/// Execute compiled Lua and capture everything it prints, so `--@expect-run:`
+/// can be compared against real stdout.
+#[cfg(feature = "mlua")]
+fn capture_run(code: &str) -> Result<String, String> {
+	use std::sync::{Arc, Mutex};
+	let lua = mlua::Lua::new();
+	let buffer = Arc::new(Mutex::new(String::new()));
+	let sink = buffer.clone();
+	let print = lua
+		.create_function(move |_, args: mlua::Variadic<String>| {
+			let mut out = sink.lock().unwrap();
+			out.push_str(&args.join("\t"));
+			out.push('\n');
+			Ok(())
+		})
+		.map_err(|e| e.to_string())?;
+	lua.globals().set("print", print).map_err(|e| e.to_string())?;
+	lua.load(code).exec().map_err(|e| e.to_string())?;
+	let output = buffer.lock().unwrap().clone();
+	Ok(output)
+}
+
+/// Check a single file against its expectations, returning `Ok(())` on pass or
+/// `Err(reason)` describing the first failure.
+fn check_file(filepath: &str, realname: &str, options: &Options) -> Result<(), String> {
+	let source = std::fs::read_to_string(filepath).map_err(|e| e.to_string())?;
+	let mut expectations = parse_directives(&source);
+
+	// A sibling `<file>.lua.expected` stands in for an `--@expect-output:` block.
+	let stem = filepath.strip_suffix(".clue").unwrap_or(filepath);
+	let expected_file = format!("{stem}.lua.expected");
+	if expectations.output.is_none() {
+		if let Ok(contents) = std::fs::read_to_string(&expected_file) {
+			expectations.output = Some(contents);
+		}
+	}
+
+	let result = compile_code(source, realname.to_owned(), 0, options, &crate::emit::Emit::default());
+
+	if let Some(substring) = &expectations.error {
+		return match result {
+			Ok(_) => Err(format!("expected error containing \"{substring}\" but compiled")),
+			Err(e) if e.contains(substring) => Ok(()),
+			Err(e) => Err(format!("expected error containing \"{substring}\" but got:\n    {e}")),
+		};
+	}
+
+	let code = result.map_err(|e| format!("unexpected compile error:\n    {e}"))?;
+
+	if let Some(expected) = &expectations.output {
+		if let Some(report) = diff(expected, &code) {
+			return Err(format!("output mismatch:\n{report}"));
+		}
+	}
+
+	if let Some(expected) = &expectations.run {
+		#[cfg(feature = "mlua")]
+		{
+			let stdout = capture_run(&code).map_err(|e| format!("run failed:\n    {e}"))?;
+			if let Some(report) = diff(expected, &stdout) {
+				return Err(format!("run output mismatch:\n{report}"));
+			}
+		}
+		#[cfg(not(feature = "mlua"))]
+		let _ = expected;
+	}
+
+	Ok(())
+}
+
+/// Discover and run every test under `path`, printing a summary and returning
+/// an `Err` (which `main` turns into a non-zero exit) if anything failed.
+pub fn run(path: &str, options: &Options) -> Result<(), String> {
+	if !Path::new(path).is_dir() {
+		return Err(format!("The test path \"{path}\" is not a directory"));
+	}
+	let files = check_for_files(path, String::new()).map_err(|e| e.to_string())?;
+	let total = files.len();
+	let mut failed = 0usize;
+	for (filepath, realname) in files {
+		match check_file(&filepath, &realname, options) {
+			Ok(()) => println!("ok    {filepath}"),
+			Err(reason) => {
+				failed += 1;
+				println!("FAIL  {filepath}: {reason}");
+			}
+		}
+	}
+	println!("\n{} passed, {failed} failed, {total} total", total - failed);
+	if failed == 0 {
+		Ok(())
+	} else {
+		Err(format!("{failed} test(s) failed"))
+	}
+}